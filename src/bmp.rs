@@ -0,0 +1,143 @@
+use orbclient::{Color, Renderer};
+
+use {Image, ImageError};
+
+/// Parse a BMP image from raw bytes
+pub fn parse(data: &[u8]) -> Result<Image, ImageError> {
+    parse_with(data, |color| color)
+}
+
+/// Parse a BMP image from raw bytes, applying `f` to each pixel as it is
+/// read off the scanline instead of in a second pass over the buffer
+pub fn parse_with<F: Fn(Color) -> Color>(data: &[u8], f: F) -> Result<Image, ImageError> {
+    if data.len() < 54 {
+        return Err(ImageError::NotEnoughData);
+    }
+
+    if &data[0..2] != b"BM" {
+        return Err(ImageError::FormatError("invalid bmp signature".to_string()));
+    }
+
+    let data_offset = read_u32(data, 10) as usize;
+    let header_size = read_u32(data, 14);
+    if header_size < 40 {
+        return Err(ImageError::FormatError(format!("unsupported bmp header size {}", header_size)));
+    }
+
+    let width = read_i32(data, 18);
+    let height = read_i32(data, 22);
+    let bpp = read_u16(data, 28);
+    let compression = read_u32(data, 30);
+
+    if compression != 0 {
+        return Err(ImageError::UnsupportedFormat(format!("bmp compression {}", compression)));
+    }
+
+    if bpp != 24 && bpp != 32 {
+        return Err(ImageError::UnsupportedFormat(format!("bmp bit depth {}", bpp)));
+    }
+
+    let w = width.unsigned_abs();
+    let h = height.unsigned_abs();
+    let top_down = height < 0;
+
+    let bytes_per_pixel = (bpp / 8) as usize;
+    let row_size = ((w as usize * bytes_per_pixel + 3) / 4) * 4;
+
+    let mut pixels = vec![Color { data: 0 }; w as usize * h as usize].into_boxed_slice();
+
+    for y in 0..h as usize {
+        let src_y = if top_down { y } else { h as usize - 1 - y };
+        let row_start = data_offset + src_y * row_size;
+        if row_start + w as usize * bytes_per_pixel > data.len() {
+            return Err(ImageError::NotEnoughData);
+        }
+
+        for x in 0..w as usize {
+            let offset = row_start + x * bytes_per_pixel;
+            let b = data[offset];
+            let g = data[offset + 1];
+            let r = data[offset + 2];
+            let a = if bytes_per_pixel == 4 { data[offset + 3] } else { 255 };
+            pixels[y * w as usize + x] = f(Color::rgba(r, g, b, a));
+        }
+    }
+
+    Image::from_data(w, h, pixels)
+}
+
+/// Encode an image as a 32bpp BMP, returning the raw bytes
+pub fn encode(image: &Image) -> Result<Vec<u8>, ImageError> {
+    let w = image.width();
+    let h = image.height();
+    let data = image.data();
+
+    let row_size = w as usize * 4;
+    let pixel_data_size = row_size * h as usize;
+    let file_size = 54 + pixel_data_size;
+
+    let mut out = Vec::with_capacity(file_size);
+
+    // BITMAPFILEHEADER
+    out.extend_from_slice(b"BM");
+    write_u32(&mut out, file_size as u32);
+    write_u32(&mut out, 0);
+    write_u32(&mut out, 54);
+
+    // BITMAPINFOHEADER
+    write_u32(&mut out, 40);
+    write_i32(&mut out, w as i32);
+    write_i32(&mut out, h as i32);
+    write_u16(&mut out, 1);
+    write_u16(&mut out, 32);
+    write_u32(&mut out, 0);
+    write_u32(&mut out, pixel_data_size as u32);
+    write_i32(&mut out, 0);
+    write_i32(&mut out, 0);
+    write_u32(&mut out, 0);
+    write_u32(&mut out, 0);
+
+    // Pixel data, bottom-up, BGRA
+    for y in (0..h as usize).rev() {
+        for x in 0..w as usize {
+            let color = data[y * w as usize + x];
+            out.push(color.b());
+            out.push(color.g());
+            out.push(color.r());
+            out.push(color.a());
+        }
+    }
+
+    Ok(out)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    (data[offset] as u16) | ((data[offset + 1] as u16) << 8)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    (data[offset] as u32)
+        | ((data[offset + 1] as u32) << 8)
+        | ((data[offset + 2] as u32) << 16)
+        | ((data[offset + 3] as u32) << 24)
+}
+
+fn read_i32(data: &[u8], offset: usize) -> i32 {
+    read_u32(data, offset) as i32
+}
+
+fn write_u16(out: &mut Vec<u8>, value: u16) {
+    out.push(value as u8);
+    out.push((value >> 8) as u8);
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.push(value as u8);
+    out.push((value >> 8) as u8);
+    out.push((value >> 16) as u8);
+    out.push((value >> 24) as u8);
+}
+
+fn write_i32(out: &mut Vec<u8>, value: i32) {
+    write_u32(out, value as u32);
+}
@@ -0,0 +1,52 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Errors that can occur while loading, decoding, or resizing an image
+#[derive(Debug)]
+pub enum ImageError {
+    /// An I/O error occurred while reading or writing a file
+    Io(io::Error),
+    /// The file's contents could not be parsed as the expected format
+    FormatError(String),
+    /// The given width/height did not match the amount of pixel data
+    DimensionError,
+    /// The format, or a feature of it, is not supported by the decoders or
+    /// encoders
+    UnsupportedFormat(String),
+    /// The file ended before all of the expected data was read
+    NotEnoughData,
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImageError::Io(ref err) => write!(f, "I/O error: {}", err),
+            ImageError::FormatError(ref message) => write!(f, "format error: {}", message),
+            ImageError::DimensionError => write!(f, "image dimensions do not match the amount of data given"),
+            ImageError::UnsupportedFormat(ref format) => write!(f, "unsupported format: {}", format),
+            ImageError::NotEnoughData => write!(f, "not enough data to decode image"),
+        }
+    }
+}
+
+impl Error for ImageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ImageError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(err: io::Error) -> Self {
+        ImageError::Io(err)
+    }
+}
+
+impl From<ImageError> for String {
+    fn from(err: ImageError) -> Self {
+        err.to_string()
+    }
+}
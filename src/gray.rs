@@ -0,0 +1,48 @@
+use orbclient::{Color, Renderer};
+
+use ImageError;
+
+/// A single-channel 8-bit-per-pixel image, useful for masks and thresholding
+/// without the 4x memory overhead of a full `Color` buffer
+pub struct GrayImage {
+    w: u32,
+    h: u32,
+    data: Box<[u8]>
+}
+
+impl GrayImage {
+    /// Create a new image from a boxed slice of gray values
+    pub fn from_data(width: u32, height: u32, data: Box<[u8]>) -> Result<Self, ImageError> {
+        if (width * height) as usize != data.len() {
+            return Err(ImageError::DimensionError)
+        }
+
+        Ok(GrayImage {
+            w: width,
+            h: height,
+            data: data,
+        })
+    }
+
+    /// Get the width of the image in pixels
+    pub fn width(&self) -> u32 {
+        self.w
+    }
+
+    /// Get the height of the image in pixels
+    pub fn height(&self) -> u32 {
+        self.h
+    }
+
+    /// Return a reference to the raw gray values making up the image
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Draw the image on a window, expanding each gray value to an opaque
+    /// `Color`
+    pub fn draw<R: Renderer>(&self, renderer: &mut R, x: i32, y: i32) {
+        let colors: Vec<Color> = self.data.iter().map(|&gray| Color::rgb(gray, gray, gray)).collect();
+        renderer.image(x, y, self.w, self.h, &colors);
+    }
+}
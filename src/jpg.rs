@@ -0,0 +1,19 @@
+use orbclient::Color;
+
+use {Image, ImageError};
+
+/// Parse a JPEG image from raw bytes
+///
+/// JPEG decoding is not implemented yet; this exists so callers can match on
+/// the `.jpg`/`.jpeg` extension without special-casing it.
+pub fn parse(data: &[u8]) -> Result<Image, ImageError> {
+    parse_with(data, |color| color)
+}
+
+/// Parse a JPEG image from raw bytes, applying `f` to each decoded pixel
+///
+/// JPEG decoding is not implemented yet; this exists so callers can match on
+/// the `.jpg`/`.jpeg` extension without special-casing it.
+pub fn parse_with<F: Fn(Color) -> Color>(_data: &[u8], _f: F) -> Result<Image, ImageError> {
+    Err(ImageError::UnsupportedFormat("jpg decoding is not implemented".to_string()))
+}
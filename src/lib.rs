@@ -6,20 +6,35 @@ extern crate resize;
 
 use std::{cmp, slice};
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::path::Path;
 
 use orbclient::{Color, Renderer};
 
 pub use bmp::parse as parse_bmp;
+pub use error::ImageError;
+pub use gray::GrayImage;
 pub use jpg::parse as parse_jpg;
 pub use png::parse as parse_png;
 pub use resize::Type as ResizeType;
 
 pub mod bmp;
+pub mod error;
+pub mod gray;
 pub mod jpg;
 pub mod png;
 
+/// How source and destination pixels are combined in `ImageRoi::draw_blended`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    /// `out = src.a * src + (1 - src.a) * dst`
+    SourceOver,
+    /// `out = src.a * (src * dst) + (1 - src.a) * dst`
+    Multiply,
+    /// `out = src.a * min(src + dst, 255) + (1 - src.a) * dst`
+    Add,
+}
+
 pub struct ImageRoi<'a> {
     x: u32,
     y: u32,
@@ -41,6 +56,58 @@ impl<'a> ImageRoi<'a> {
             y += 1;
         }
     }
+
+    /// Draw the ROI on a window, alpha-compositing each source pixel with
+    /// the destination instead of overwriting it. Reads the destination
+    /// pixels back through `Renderer::data`/`data_mut` so translucent
+    /// sprites and UI overlays blend with what is already on screen
+    pub fn draw_blended<R: Renderer>(&self, renderer: &mut R, x: i32, y: i32, mode: BlendMode) {
+        let dst_w = renderer.width() as i32;
+        let dst_h = renderer.height() as i32;
+        let src_stride = self.image.w;
+
+        for row in 0..self.h {
+            let dst_y = y + row as i32;
+            if dst_y < 0 || dst_y >= dst_h {
+                continue;
+            }
+
+            for col in 0..self.w {
+                let dst_x = x + col as i32;
+                if dst_x < 0 || dst_x >= dst_w {
+                    continue;
+                }
+
+                let src_index = ((self.y + row) * src_stride + (self.x + col)) as usize;
+                let src = self.image.data[src_index];
+
+                let dst_index = (dst_y as u32 * renderer.width() + dst_x as u32) as usize;
+                let dst = renderer.data()[dst_index];
+
+                renderer.data_mut()[dst_index] = blend(src, dst, mode);
+            }
+        }
+    }
+}
+
+fn blend(src: Color, dst: Color, mode: BlendMode) -> Color {
+    let src_a = src.a() as f32 / 255.0;
+    let dst_a = 1.0 - src_a;
+
+    let blend_channel = |s: u8, d: u8| -> f32 {
+        match mode {
+            BlendMode::SourceOver => s as f32,
+            BlendMode::Multiply => (s as f32 * d as f32) / 255.0,
+            BlendMode::Add => cmp::min(s as u32 + d as u32, 255) as f32,
+        }
+    };
+
+    let r = (src_a * blend_channel(src.r(), dst.r()) + dst_a * dst.r() as f32).round() as u8;
+    let g = (src_a * blend_channel(src.g(), dst.g()) + dst_a * dst.g() as f32).round() as u8;
+    let b = (src_a * blend_channel(src.b(), dst.b()) + dst_a * dst.b() as f32).round() as u8;
+    let a = cmp::max(src.a(), dst.a());
+
+    Color::rgba(r, g, b, a)
 }
 
 pub struct Image {
@@ -61,9 +128,9 @@ impl Image {
     }
 
     /// Create a new image from a boxed slice of colors
-    pub fn from_data(width: u32, height: u32, data: Box<[Color]>) -> Result<Self, String> {
+    pub fn from_data(width: u32, height: u32, data: Box<[Color]>) -> Result<Self, ImageError> {
         if (width * height) as usize != data.len() {
-            return Err("not enough or too much data given compared to width and height".to_string())
+            return Err(ImageError::DimensionError)
         }
 
         Ok(Image {
@@ -74,22 +141,104 @@ impl Image {
     }
 
     /// Load an image from file path. Supports BMP and PNG
-    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, String> {
-        let mut file = try!(File::open(&path).map_err(|err| format!("failed to open image: {}", err)));
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        let mut file = try!(File::open(&path));
         let mut data: Vec<u8> = Vec::new();
-        let _ = try!(file.read_to_end(&mut data).map_err(|err| format!("failed to read image: {}", err)));
-        //TODO: Use magic to match file instead of extension
+        let _ = try!(file.read_to_end(&mut data));
+
+        if let Ok(image) = Self::load(&data) {
+            return Ok(image);
+        }
+
         match path.as_ref().extension() {
             Some(extension_os) => match extension_os.to_str() {
                 Some(extension) => match extension.to_lowercase().as_str() {
                     "bmp" => parse_bmp(&data),
                     "jpg" | "jpeg" => parse_jpg(&data),
                     "png" => parse_png(&data),
-                    other => Err(format!("unknown image extension: {}", other))
+                    other => Err(ImageError::UnsupportedFormat(other.to_string()))
+                },
+                None => Err(ImageError::FormatError("image extension not valid unicode".to_string()))
+            },
+            None => Err(ImageError::FormatError("no image extension".to_string()))
+        }
+    }
+
+    /// Load just a sub-rectangle of a PNG file, for tiled map viewers and
+    /// thumbnail strips that only need a crop of a potentially large source
+    /// file. Only the crop is converted to `Color`s; the whole file is still
+    /// read, inflated and unfiltered
+    pub fn from_path_region<P: AsRef<Path>>(path: P, x: u32, y: u32, w: u32, h: u32) -> Result<Self, ImageError> {
+        let mut file = try!(File::open(&path));
+        let mut data: Vec<u8> = Vec::new();
+        let _ = try!(file.read_to_end(&mut data));
+        png::parse_region(&data, x, y, w, h)
+    }
+
+    /// Load an image by sniffing its format from the leading bytes, instead
+    /// of trusting a file extension. Falls back to `UnsupportedFormat` if no
+    /// known signature matches
+    pub fn load(data: &[u8]) -> Result<Self, ImageError> {
+        if data.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]) {
+            parse_png(data)
+        } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            parse_jpg(data)
+        } else if data.starts_with(b"BM") {
+            parse_bmp(data)
+        } else {
+            Err(ImageError::UnsupportedFormat("no recognized magic bytes".to_string()))
+        }
+    }
+
+    /// Load an image by sniffing its format from the leading bytes, applying
+    /// `f` to each pixel as it comes off the decoder's scanlines. Falls back
+    /// to `UnsupportedFormat` if no known signature matches
+    pub fn load_with<F: Fn(Color) -> Color>(data: &[u8], f: F) -> Result<Self, ImageError> {
+        if data.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]) {
+            png::parse_with(data, f)
+        } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            jpg::parse_with(data, f)
+        } else if data.starts_with(b"BM") {
+            bmp::parse_with(data, f)
+        } else {
+            Err(ImageError::UnsupportedFormat("no recognized magic bytes".to_string()))
+        }
+    }
+
+    /// Load an image from file path, applying `f` to every pixel as it is
+    /// decoded, directly in the `bmp`/`png` scanline loops. Useful for
+    /// premultiplying alpha, applying gamma, or tinting without a second pass
+    /// over the buffer
+    pub fn from_path_with<P: AsRef<Path>, F: Fn(Color) -> Color>(path: P, f: F) -> Result<Self, ImageError> {
+        let mut file = try!(File::open(&path));
+        let mut data: Vec<u8> = Vec::new();
+        let _ = try!(file.read_to_end(&mut data));
+
+        if let Ok(image) = Self::load_with(&data, &f) {
+            return Ok(image);
+        }
+
+        match path.as_ref().extension() {
+            Some(extension_os) => match extension_os.to_str() {
+                Some(extension) => match extension.to_lowercase().as_str() {
+                    "bmp" => bmp::parse_with(&data, f),
+                    "jpg" | "jpeg" => jpg::parse_with(&data, f),
+                    "png" => png::parse_with(&data, f),
+                    other => Err(ImageError::UnsupportedFormat(other.to_string()))
                 },
-                None => Err("image extension not valid unicode".to_string())
+                None => Err(ImageError::FormatError("image extension not valid unicode".to_string()))
             },
-            None => Err("no image extension".to_string())
+            None => Err(ImageError::FormatError("no image extension".to_string()))
+        }
+    }
+
+    /// Return a copy of this image with `f` applied to every pixel
+    pub fn map<F: Fn(Color) -> Color>(&self, f: F) -> Image {
+        let data: Box<[Color]> = self.data.iter().map(|&color| f(color)).collect();
+        Image {
+            w: self.w,
+            h: self.h,
+            data: data,
         }
     }
 
@@ -98,8 +247,64 @@ impl Image {
         Self::new(0, 0)
     }
 
+    /// Convert to a single-channel `GrayImage` using the Rec. 601 luma
+    /// weights (`0.299R + 0.587G + 0.114B`)
+    pub fn to_grayscale(&self) -> GrayImage {
+        let data: Box<[u8]> = self.data.iter().map(|&color| {
+            let luma = 0.299 * color.r() as f32 + 0.587 * color.g() as f32 + 0.114 * color.b() as f32;
+            luma.round() as u8
+        }).collect();
+
+        GrayImage::from_data(self.w, self.h, data).unwrap()
+    }
+
+    /// Return a copy of this image with every channel's value inverted
+    /// (alpha is left untouched)
+    pub fn invert(&self) -> Image {
+        self.map(|color| Color::rgba(255 - color.r(), 255 - color.g(), 255 - color.b(), color.a()))
+    }
+
+    /// Return a copy of this image with a hue/saturation/lightness delta
+    /// applied to every pixel. `dh` is in degrees and wraps around; `ds` and
+    /// `dl` are in the 0.0-1.0 range and are clamped after being applied
+    pub fn adjust_hsl(&self, dh: f32, ds: f32, dl: f32) -> Image {
+        self.map(|color| {
+            let (h, s, l) = rgb_to_hsl(color.r(), color.g(), color.b());
+
+            let mut h = (h + dh) % 360.0;
+            if h < 0.0 {
+                h += 360.0;
+            }
+            let s = (s + ds).max(0.0).min(1.0);
+            let l = (l + dl).max(0.0).min(1.0);
+
+            let (r, g, b) = hsl_to_rgb(h, s, l);
+            Color::rgba(r, g, b, color.a())
+        })
+    }
+
+    /// Save the image to a file path. The format is chosen from the
+    /// extension (`.bmp` or `.png`); other extensions are rejected
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ImageError> {
+        let encoded = try!(match path.as_ref().extension() {
+            Some(extension_os) => match extension_os.to_str() {
+                Some(extension) => match extension.to_lowercase().as_str() {
+                    "bmp" => bmp::encode(self),
+                    "png" => png::encode(self),
+                    other => Err(ImageError::UnsupportedFormat(other.to_string()))
+                },
+                None => Err(ImageError::FormatError("image extension not valid unicode".to_string()))
+            },
+            None => Err(ImageError::FormatError("no image extension".to_string()))
+        });
+
+        let mut file = try!(File::create(&path));
+        try!(file.write_all(&encoded));
+        Ok(())
+    }
+
     // Get a resized version of the image
-    pub fn resize(&self, w: u32, h: u32, resize_type: ResizeType) -> Result<Self, String> {
+    pub fn resize(&self, w: u32, h: u32, resize_type: ResizeType) -> Result<Self, ImageError> {
         let mut dst_color = vec![Color { data: 0 }; w as usize * h as usize].into_boxed_slice();
 
         let src = unsafe {
@@ -118,6 +323,40 @@ impl Image {
         Image::from_data(w, h, dst_color)
     }
 
+    /// Resize the image to the largest size that fits inside `max_w`x`max_h`
+    /// while preserving the source aspect ratio
+    pub fn thumbnail(&self, max_w: u32, max_h: u32, resize_type: ResizeType) -> Result<Self, ImageError> {
+        let scale = (max_w as f32 / self.w as f32).min(max_h as f32 / self.h as f32);
+        let w = cmp::max(1, (self.w as f32 * scale).round() as u32);
+        let h = cmp::max(1, (self.h as f32 * scale).round() as u32);
+
+        self.resize(w, h, resize_type)
+    }
+
+    /// Resize the image to cover a `w`x`h` box while preserving aspect
+    /// ratio, then center-crop down to exactly `w`x`h`
+    pub fn resize_fill(&self, w: u32, h: u32, resize_type: ResizeType) -> Result<Self, ImageError> {
+        let scale = (w as f32 / self.w as f32).max(h as f32 / self.h as f32);
+        let scaled_w = cmp::max(1, (self.w as f32 * scale).round() as u32);
+        let scaled_h = cmp::max(1, (self.h as f32 * scale).round() as u32);
+
+        let scaled = try!(self.resize(scaled_w, scaled_h, resize_type));
+
+        let crop_x = (scaled_w - w) / 2;
+        let crop_y = (scaled_h - h) / 2;
+        let roi = scaled.roi(crop_x, crop_y, w, h);
+
+        let mut data = vec![Color { data: 0 }; w as usize * h as usize].into_boxed_slice();
+        for row in 0..roi.h {
+            let src_start = ((roi.y + row) * scaled_w + roi.x) as usize;
+            let dst_start = (row * roi.w) as usize;
+            data[dst_start..dst_start + roi.w as usize]
+                .copy_from_slice(&scaled.data[src_start..src_start + roi.w as usize]);
+        }
+
+        Image::from_data(roi.w, roi.h, data)
+    }
+
     /// Get a piece of the image
     pub fn roi<'a>(&'a self, x: u32, y: u32, w: u32, h: u32) -> ImageRoi<'a> {
         let x1 = cmp::min(x, self.width());
@@ -145,6 +384,71 @@ impl Image {
     }
 }
 
+fn rgb_to_hsl(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    let r = r as f32 / 255.0;
+    let g = g as f32 / 255.0;
+    let b = b as f32 / 255.0;
+
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+
+    if (max - min).abs() < f32::EPSILON {
+        return (0.0, 0.0, l);
+    }
+
+    let delta = max - min;
+    let s = if l > 0.5 { delta / (2.0 - max - min) } else { delta / (max + min) };
+
+    let mut h = if max == r {
+        (g - b) / delta + if g < b { 6.0 } else { 0.0 }
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    h *= 60.0;
+
+    (h, s, l)
+}
+
+fn hsl_to_rgb(h: f32, s: f32, l: f32) -> (u8, u8, u8) {
+    if s.abs() < f32::EPSILON {
+        let v = (l * 255.0).round() as u8;
+        return (v, v, v);
+    }
+
+    let q = if l < 0.5 { l * (1.0 + s) } else { l + s - l * s };
+    let p = 2.0 * l - q;
+    let h = h / 360.0;
+
+    let r = hue_to_rgb(p, q, h + 1.0 / 3.0);
+    let g = hue_to_rgb(p, q, h);
+    let b = hue_to_rgb(p, q, h - 1.0 / 3.0);
+
+    ((r * 255.0).round() as u8, (g * 255.0).round() as u8, (b * 255.0).round() as u8)
+}
+
+fn hue_to_rgb(p: f32, q: f32, t: f32) -> f32 {
+    let mut t = t;
+    if t < 0.0 {
+        t += 1.0;
+    }
+    if t > 1.0 {
+        t -= 1.0;
+    }
+
+    if t < 1.0 / 6.0 {
+        p + (q - p) * 6.0 * t
+    } else if t < 1.0 / 2.0 {
+        q
+    } else if t < 2.0 / 3.0 {
+        p + (q - p) * (2.0 / 3.0 - t) * 6.0
+    } else {
+        p
+    }
+}
+
 impl Renderer for Image {
     /// Get the width of the image in pixels
     fn width(&self) -> u32 {
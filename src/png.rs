@@ -0,0 +1,872 @@
+use std::collections::HashMap;
+
+use orbclient::{Color, Renderer};
+
+use {Image, ImageError};
+
+const SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// The decoded, unfiltered scanlines of a PNG along with the header fields
+/// needed to turn them into pixels
+struct Scanlines {
+    width: u32,
+    height: u32,
+    color_type: u8,
+    channels: usize,
+    palette: Vec<(u8, u8, u8)>,
+    data: Vec<u8>,
+}
+
+/// Walk the chunk stream, collect IHDR/PLTE/IDAT, and inflate + unfilter the
+/// image data. Shared by `parse` and `parse_region` so chunk parsing and
+/// IDAT handling only need to be hardened in one place
+fn decode_scanlines(data: &[u8]) -> Result<Scanlines, ImageError> {
+    if data.len() < 8 || data[0..8] != SIGNATURE {
+        return Err(ImageError::FormatError("invalid png signature".to_string()));
+    }
+
+    let mut width = 0u32;
+    let mut height = 0u32;
+    let mut bit_depth = 0u8;
+    let mut color_type = 0u8;
+    let mut palette: Vec<(u8, u8, u8)> = Vec::new();
+    let mut idat: Vec<u8> = Vec::new();
+
+    let mut offset = 8;
+    while offset + 8 <= data.len() {
+        let length = read_u32(data, offset) as usize;
+        let kind = &data[offset + 4..offset + 8];
+        let chunk_start = offset + 8;
+        if chunk_start + length + 4 > data.len() {
+            return Err(ImageError::NotEnoughData);
+        }
+        let chunk_data = &data[chunk_start..chunk_start + length];
+
+        match kind {
+            b"IHDR" => {
+                if length < 13 {
+                    return Err(ImageError::FormatError("IHDR too short".to_string()));
+                }
+                width = read_u32(chunk_data, 0);
+                height = read_u32(chunk_data, 4);
+                bit_depth = chunk_data[8];
+                color_type = chunk_data[9];
+                if chunk_data[10] != 0 {
+                    return Err(ImageError::UnsupportedFormat("png compression method".to_string()));
+                }
+                if chunk_data[12] != 0 {
+                    return Err(ImageError::UnsupportedFormat("interlaced png".to_string()));
+                }
+            }
+            b"PLTE" => {
+                for entry in chunk_data.chunks(3) {
+                    if entry.len() == 3 {
+                        palette.push((entry[0], entry[1], entry[2]));
+                    }
+                }
+            }
+            b"IDAT" => {
+                idat.extend_from_slice(chunk_data);
+            }
+            b"IEND" => break,
+            _ => {}
+        }
+
+        offset = chunk_start + length + 4;
+    }
+
+    if width == 0 || height == 0 {
+        return Err(ImageError::FormatError("missing IHDR".to_string()));
+    }
+
+    if bit_depth != 8 {
+        return Err(ImageError::UnsupportedFormat(format!("png bit depth {}", bit_depth)));
+    }
+
+    let channels = match color_type {
+        0 => 1,
+        2 => 3,
+        3 => 1,
+        4 => 2,
+        6 => 4,
+        other => return Err(ImageError::UnsupportedFormat(format!("png color type {}", other))),
+    };
+
+    if idat.len() < 6 {
+        return Err(ImageError::NotEnoughData);
+    }
+    let raw = try!(inflate(&idat[2..idat.len() - 4]).map_err(ImageError::FormatError));
+    let scanlines = try!(unfilter(&raw, width, height, channels));
+
+    Ok(Scanlines {
+        width: width,
+        height: height,
+        color_type: color_type,
+        channels: channels,
+        palette: palette,
+        data: scanlines,
+    })
+}
+
+fn scanline_to_color(pixel: &[u8], color_type: u8, palette: &[(u8, u8, u8)]) -> Result<Color, ImageError> {
+    Ok(match color_type {
+        0 => Color::rgb(pixel[0], pixel[0], pixel[0]),
+        2 => Color::rgb(pixel[0], pixel[1], pixel[2]),
+        3 => {
+            let (r, g, b) = *try!(palette.get(pixel[0] as usize).ok_or_else(|| ImageError::FormatError("palette index out of range".to_string())));
+            Color::rgb(r, g, b)
+        }
+        4 => Color::rgba(pixel[0], pixel[0], pixel[0], pixel[1]),
+        6 => Color::rgba(pixel[0], pixel[1], pixel[2], pixel[3]),
+        _ => unreachable!(),
+    })
+}
+
+/// Parse a PNG image from raw bytes
+pub fn parse(data: &[u8]) -> Result<Image, ImageError> {
+    parse_with(data, |color| color)
+}
+
+/// Parse a PNG image from raw bytes, applying `f` to each pixel as it is
+/// produced from the scanlines instead of in a second pass over the buffer
+pub fn parse_with<F: Fn(Color) -> Color>(data: &[u8], f: F) -> Result<Image, ImageError> {
+    let png = try!(decode_scanlines(data));
+    let (width, height, channels) = (png.width, png.height, png.channels);
+
+    let mut pixels = vec![Color { data: 0 }; (width * height) as usize].into_boxed_slice();
+    for y in 0..height as usize {
+        let row = &png.data[y * width as usize * channels..(y + 1) * width as usize * channels];
+        for x in 0..width as usize {
+            let pixel = &row[x * channels..x * channels + channels];
+            pixels[y * width as usize + x] = f(try!(scanline_to_color(pixel, png.color_type, &png.palette)));
+        }
+    }
+
+    Image::from_data(width, height, pixels)
+}
+
+/// Parse just a sub-rectangle of a PNG. Every scanline of the image is
+/// inflated and unfiltered; only the pixels inside the region are converted
+/// into the returned `Image`
+pub fn parse_region(data: &[u8], x: u32, y: u32, w: u32, h: u32) -> Result<Image, ImageError> {
+    let png = try!(decode_scanlines(data));
+    let (width, height, channels) = (png.width, png.height, png.channels);
+
+    let x1 = ::std::cmp::min(x, width);
+    let y1 = ::std::cmp::min(y, height);
+    let x2 = ::std::cmp::min(x.saturating_add(w), width);
+    let y2 = ::std::cmp::min(y.saturating_add(h), height);
+    let region_w = x2 - x1;
+    let region_h = y2 - y1;
+
+    // decode_scanlines has already unfiltered all rows of the image, including
+    // those below the region; only the in-region rows and columns are read here.
+    let mut pixels = vec![Color { data: 0 }; (region_w * region_h) as usize].into_boxed_slice();
+    for row in 0..region_h as usize {
+        let y_src = y1 as usize + row;
+        let line = &png.data[y_src * width as usize * channels..(y_src + 1) * width as usize * channels];
+        for col in 0..region_w as usize {
+            let x_src = x1 as usize + col;
+            let pixel = &line[x_src * channels..x_src * channels + channels];
+            pixels[row * region_w as usize + col] = try!(scanline_to_color(pixel, png.color_type, &png.palette));
+        }
+    }
+
+    Image::from_data(region_w, region_h, pixels)
+}
+
+/// Encode an image as a PNG (truecolor with alpha), returning the raw bytes
+pub fn encode(image: &Image) -> Result<Vec<u8>, ImageError> {
+    let w = image.width();
+    let h = image.height();
+    let data = image.data();
+
+    let mut ihdr = Vec::with_capacity(13);
+    write_u32(&mut ihdr, w);
+    write_u32(&mut ihdr, h);
+    ihdr.push(8); // bit depth
+    ihdr.push(6); // color type: truecolor + alpha
+    ihdr.push(0); // compression method
+    ihdr.push(0); // filter method
+    ihdr.push(0); // interlace method
+
+    let mut filtered = Vec::with_capacity((w as usize * 4 + 1) * h as usize);
+    let mut prev_row = vec![0u8; w as usize * 4];
+    for y in 0..h as usize {
+        let mut row = Vec::with_capacity(w as usize * 4);
+        for x in 0..w as usize {
+            let color = data[y * w as usize + x];
+            row.push(color.r());
+            row.push(color.g());
+            row.push(color.b());
+            row.push(color.a());
+        }
+        let (filter_type, filtered_row) = filter_row(&row, &prev_row, 4);
+        filtered.push(filter_type);
+        filtered.extend_from_slice(&filtered_row);
+        prev_row = row;
+    }
+
+    let idat = deflate(&filtered);
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&SIGNATURE);
+    write_chunk(&mut out, b"IHDR", &ihdr);
+    write_chunk(&mut out, b"IDAT", &idat);
+    write_chunk(&mut out, b"IEND", &[]);
+
+    Ok(out)
+}
+
+/// Try every PNG filter type for a scanline and keep whichever minimizes the
+/// sum of absolute values of the filtered bytes (treated as signed), as
+/// LodePNG does.
+fn filter_row(row: &[u8], prev_row: &[u8], bpp: usize) -> (u8, Vec<u8>) {
+    let candidates: [u8; 5] = [0, 1, 2, 3, 4];
+    let mut best_filter = 0u8;
+    let mut best_row = row.to_vec();
+    let mut best_score = score(&best_row);
+
+    for &filter_type in &candidates[1..] {
+        let mut candidate = vec![0u8; row.len()];
+        for i in 0..row.len() {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let b = prev_row[i];
+            let c = if i >= bpp { prev_row[i - bpp] } else { 0 };
+            let x = row[i];
+            candidate[i] = match filter_type {
+                1 => x.wrapping_sub(a),
+                2 => x.wrapping_sub(b),
+                3 => x.wrapping_sub(((a as u16 + b as u16) / 2) as u8),
+                4 => x.wrapping_sub(paeth(a, b, c)),
+                _ => unreachable!(),
+            };
+        }
+        let candidate_score = score(&candidate);
+        if candidate_score < best_score {
+            best_score = candidate_score;
+            best_filter = filter_type;
+            best_row = candidate;
+        }
+    }
+
+    (best_filter, best_row)
+}
+
+fn score(row: &[u8]) -> u32 {
+    row.iter().map(|&b| (b as i8).unsigned_abs() as u32).sum()
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i32 + b as i32 - c as i32;
+    let pa = (p - a as i32).abs();
+    let pb = (p - b as i32).abs();
+    let pc = (p - c as i32).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn unfilter(raw: &[u8], width: u32, height: u32, channels: usize) -> Result<Vec<u8>, ImageError> {
+    let stride = width as usize * channels;
+    let bpp = channels;
+    if raw.len() < (stride + 1) * height as usize {
+        return Err(ImageError::NotEnoughData);
+    }
+
+    let mut out = vec![0u8; stride * height as usize];
+    let mut offset = 0;
+    for y in 0..height as usize {
+        let filter_type = raw[offset];
+        offset += 1;
+        let src = &raw[offset..offset + stride];
+        offset += stride;
+
+        for i in 0..stride {
+            let a = if i >= bpp { out[y * stride + i - bpp] } else { 0 };
+            let b = if y > 0 { out[(y - 1) * stride + i] } else { 0 };
+            let c = if y > 0 && i >= bpp { out[(y - 1) * stride + i - bpp] } else { 0 };
+            let x = src[i];
+            out[y * stride + i] = match filter_type {
+                0 => x,
+                1 => x.wrapping_add(a),
+                2 => x.wrapping_add(b),
+                3 => x.wrapping_add(((a as u16 + b as u16) / 2) as u8),
+                4 => x.wrapping_add(paeth(a, b, c)),
+                other => return Err(ImageError::FormatError(format!("unknown png filter type {}", other))),
+            };
+        }
+    }
+
+    Ok(out)
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    write_u32(out, data.len() as u32);
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let mut crc_input = Vec::with_capacity(4 + data.len());
+    crc_input.extend_from_slice(kind);
+    crc_input.extend_from_slice(data);
+    write_u32(out, crc32(&crc_input));
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    ((data[offset] as u32) << 24)
+        | ((data[offset + 1] as u32) << 16)
+        | ((data[offset + 2] as u32) << 8)
+        | (data[offset + 3] as u32)
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.push((value >> 24) as u8);
+    out.push((value >> 16) as u8);
+    out.push((value >> 8) as u8);
+    out.push(value as u8);
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFFFFFF;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0xEDB88320;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    crc ^ 0xFFFFFFFF
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    for &byte in data {
+        a = (a + byte as u32) % MOD_ADLER;
+        b = (b + a) % MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+/// A single LZ77 token: either a literal byte, or a length/distance
+/// back-reference into data already emitted.
+enum Token {
+    Literal(u8),
+    Match { length: usize, distance: usize },
+}
+
+/// Greedily tokenize `data` into literals and back-references, using a hash
+/// chain over 3-byte prefixes to find matches (bounded to `MAX_CHAIN` probes
+/// per position so encoding stays linear-ish on pathological input).
+fn lz77_tokens(data: &[u8]) -> Vec<Token> {
+    const MIN_MATCH: usize = 3;
+    const MAX_MATCH: usize = 258;
+    const WINDOW: usize = 32768;
+    const MAX_CHAIN: usize = 32;
+
+    let mut tokens = Vec::new();
+    let mut chains: HashMap<[u8; 3], Vec<usize>> = HashMap::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+
+        if i + MIN_MATCH <= data.len() {
+            let key = [data[i], data[i + 1], data[i + 2]];
+            if let Some(positions) = chains.get(&key) {
+                let max_len = ::std::cmp::min(MAX_MATCH, data.len() - i);
+                for &pos in positions.iter().rev().take(MAX_CHAIN) {
+                    if i - pos > WINDOW {
+                        break;
+                    }
+                    let mut len = 0;
+                    while len < max_len && data[pos + len] == data[i + len] {
+                        len += 1;
+                    }
+                    if len > best_len {
+                        best_len = len;
+                        best_dist = i - pos;
+                    }
+                }
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            for offset in 0..best_len {
+                if i + offset + MIN_MATCH <= data.len() {
+                    let key = [data[i + offset], data[i + offset + 1], data[i + offset + 2]];
+                    chains.entry(key).or_insert_with(Vec::new).push(i + offset);
+                }
+            }
+            tokens.push(Token::Match { length: best_len, distance: best_dist });
+            i += best_len;
+        } else {
+            if i + MIN_MATCH <= data.len() {
+                let key = [data[i], data[i + 1], data[i + 2]];
+                chains.entry(key).or_insert_with(Vec::new).push(i);
+            }
+            tokens.push(Token::Literal(data[i]));
+            i += 1;
+        }
+    }
+
+    tokens
+}
+
+/// The length/distance alphabets defined by the deflate spec share one shape:
+/// a base value per symbol plus some number of extra bits to add to it. Find
+/// the symbol whose base is the largest one not exceeding `value`.
+fn base_symbol(value: usize, base: &[u16], extra: &[u8]) -> (u16, u32, u32) {
+    let mut index = 0;
+    for i in 0..base.len() {
+        if base[i] as usize <= value {
+            index = i;
+        } else {
+            break;
+        }
+    }
+    let extra_bits = extra[index] as u32;
+    let extra_value = (value - base[index] as usize) as u32;
+    (index as u16, extra_value, extra_bits)
+}
+
+/// The length alphabet is appended after the 256 literal symbols and the
+/// end-of-block symbol in the literal/length tree.
+fn length_symbol(length: usize) -> (u16, u32, u32) {
+    let (index, extra_value, extra_bits) = base_symbol(length, &LENGTH_BASE, &LENGTH_EXTRA);
+    (257 + index, extra_value, extra_bits)
+}
+
+fn distance_symbol(distance: usize) -> (u16, u32, u32) {
+    base_symbol(distance, &DIST_BASE, &DIST_EXTRA)
+}
+
+/// The fixed (static) Huffman code lengths defined by the deflate spec,
+/// shared by the decoder (`fixed_trees`) and the encoder (`deflate`)
+fn fixed_lengths() -> ([u8; 288], [u8; 30]) {
+    let mut lit_lengths = [0u8; 288];
+    for i in 0..144 {
+        lit_lengths[i] = 8;
+    }
+    for i in 144..256 {
+        lit_lengths[i] = 9;
+    }
+    for i in 256..280 {
+        lit_lengths[i] = 7;
+    }
+    for i in 280..288 {
+        lit_lengths[i] = 8;
+    }
+    let dist_lengths = [5u8; 30];
+    (lit_lengths, dist_lengths)
+}
+
+/// Assign canonical Huffman codes to a set of code lengths, per RFC 1951
+/// 3.2.2. Returns `(code, length)` per symbol index; unused symbols get
+/// length 0.
+fn build_codes(lengths: &[u8]) -> Vec<(u32, u8)> {
+    let max_bits = lengths.iter().cloned().max().unwrap_or(0) as usize;
+    let mut bl_count = vec![0u32; max_bits + 1];
+    for &len in lengths {
+        if len > 0 {
+            bl_count[len as usize] += 1;
+        }
+    }
+
+    let mut code = 0u32;
+    let mut next_code = vec![0u32; max_bits + 1];
+    for bits in 1..=max_bits {
+        code = (code + bl_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+
+    let mut codes = vec![(0u32, 0u8); lengths.len()];
+    for (symbol, &len) in lengths.iter().enumerate() {
+        if len > 0 {
+            codes[symbol] = (next_code[len as usize], len);
+            next_code[len as usize] += 1;
+        }
+    }
+
+    codes
+}
+
+fn write_symbol(writer: &mut BitWriter, codes: &[(u32, u8)], symbol: u16) {
+    let (code, length) = codes[symbol as usize];
+    writer.write_code(code, length);
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    bit_buf: u8,
+    bit_pos: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter { out: Vec::new(), bit_buf: 0, bit_pos: 0 }
+    }
+
+    fn write_bit(&mut self, bit: u32) {
+        self.bit_buf |= ((bit & 1) as u8) << self.bit_pos;
+        self.bit_pos += 1;
+        if self.bit_pos == 8 {
+            self.out.push(self.bit_buf);
+            self.bit_buf = 0;
+            self.bit_pos = 0;
+        }
+    }
+
+    /// Write the `count` low bits of `value`, least-significant bit first
+    /// (the bit order deflate uses for everything except Huffman codes).
+    fn write_bits(&mut self, value: u32, count: u32) {
+        for i in 0..count {
+            self.write_bit((value >> i) & 1);
+        }
+    }
+
+    /// Write a canonical Huffman code, most-significant bit first (deflate's
+    /// one exception to its usual least-significant-bit-first packing).
+    fn write_code(&mut self, code: u32, length: u8) {
+        for i in (0..length as u32).rev() {
+            self.write_bit((code >> i) & 1);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_pos != 0 {
+            self.out.push(self.bit_buf);
+        }
+        self.out
+    }
+}
+
+/// Compress data into a zlib stream using a single fixed-Huffman deflate
+/// block, fed by greedy LZ77 matching, so the adaptive per-scanline
+/// filtering above actually reduces the size of the final IDAT payload.
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 16);
+    out.push(0x78); // CMF: deflate, 32k window
+    out.push(0x01); // FLG: no dict, fastest compression level, check bits valid
+
+    let (lit_lengths, dist_lengths) = fixed_lengths();
+    let lit_codes = build_codes(&lit_lengths);
+    let dist_codes = build_codes(&dist_lengths);
+
+    let mut writer = BitWriter::new();
+    writer.write_bits(1, 1); // final block
+    writer.write_bits(1, 2); // block type 1: fixed Huffman
+
+    for token in lz77_tokens(data) {
+        match token {
+            Token::Literal(byte) => write_symbol(&mut writer, &lit_codes, byte as u16),
+            Token::Match { length, distance } => {
+                let (len_symbol, len_extra, len_extra_bits) = length_symbol(length);
+                write_symbol(&mut writer, &lit_codes, len_symbol);
+                writer.write_bits(len_extra, len_extra_bits);
+
+                let (dist_symbol, dist_extra, dist_extra_bits) = distance_symbol(distance);
+                write_symbol(&mut writer, &dist_codes, dist_symbol);
+                writer.write_bits(dist_extra, dist_extra_bits);
+            }
+        }
+    }
+    write_symbol(&mut writer, &lit_codes, 256); // end of block
+
+    out.extend(writer.finish());
+    write_u32(&mut out, adler32(data));
+    out
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    byte_pos: usize,
+    bit_pos: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data: data, byte_pos: 0, bit_pos: 0 }
+    }
+
+    fn read_bit(&mut self) -> Result<u32, String> {
+        if self.byte_pos >= self.data.len() {
+            return Err("png: unexpected end of deflate stream".to_string());
+        }
+        let bit = (self.data[self.byte_pos] >> self.bit_pos) & 1;
+        self.bit_pos += 1;
+        if self.bit_pos == 8 {
+            self.bit_pos = 0;
+            self.byte_pos += 1;
+        }
+        Ok(bit as u32)
+    }
+
+    fn read_bits(&mut self, count: u32) -> Result<u32, String> {
+        let mut value = 0u32;
+        for i in 0..count {
+            value |= try!(self.read_bit()) << i;
+        }
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        if self.bit_pos != 0 {
+            self.bit_pos = 0;
+            self.byte_pos += 1;
+        }
+    }
+}
+
+struct HuffmanTree {
+    // (code length, symbol) pairs indexed implicitly via canonical decoding
+    counts: Vec<u16>,
+    symbols: Vec<u16>,
+}
+
+impl HuffmanTree {
+    fn from_lengths(lengths: &[u8]) -> Self {
+        let max_bits = lengths.iter().cloned().max().unwrap_or(0) as usize;
+        let mut counts = vec![0u16; max_bits + 1];
+        for &len in lengths {
+            if len > 0 {
+                counts[len as usize] += 1;
+            }
+        }
+
+        let mut offsets = vec![0u16; max_bits + 2];
+        for bits in 1..=max_bits {
+            offsets[bits + 1] = offsets[bits] + counts[bits];
+        }
+
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len > 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        HuffmanTree { counts: counts, symbols: symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for len in 1..self.counts.len() {
+            code |= try!(reader.read_bit()) as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err("png: invalid huffman code".to_string())
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompress a raw deflate stream (the body of the concatenated IDAT chunks
+/// with the 2-byte zlib header and 4-byte Adler-32 trailer already removed).
+fn inflate(zlib_data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = BitReader::new(zlib_data);
+    let mut out = Vec::new();
+
+    loop {
+        let is_final = try!(reader.read_bit());
+        let block_type = try!(reader.read_bits(2));
+
+        match block_type {
+            0 => {
+                reader.align_to_byte();
+                if reader.byte_pos + 4 > reader.data.len() {
+                    return Err("png: truncated stored block".to_string());
+                }
+                let len = reader.data[reader.byte_pos] as usize | ((reader.data[reader.byte_pos + 1] as usize) << 8);
+                reader.byte_pos += 4;
+                if reader.byte_pos + len > reader.data.len() {
+                    return Err("png: truncated stored block data".to_string());
+                }
+                out.extend_from_slice(&reader.data[reader.byte_pos..reader.byte_pos + len]);
+                reader.byte_pos += len;
+            }
+            1 => {
+                let (lit_tree, dist_tree) = fixed_trees();
+                try!(inflate_block(&mut reader, &lit_tree, &dist_tree, &mut out));
+            }
+            2 => {
+                let (lit_tree, dist_tree) = try!(read_dynamic_trees(&mut reader));
+                try!(inflate_block(&mut reader, &lit_tree, &dist_tree, &mut out));
+            }
+            other => return Err(format!("png: invalid deflate block type {}", other)),
+        }
+
+        if is_final == 1 {
+            break;
+        }
+    }
+
+    Ok(out)
+}
+
+fn fixed_trees() -> (HuffmanTree, HuffmanTree) {
+    let (lit_lengths, dist_lengths) = fixed_lengths();
+    (HuffmanTree::from_lengths(&lit_lengths), HuffmanTree::from_lengths(&dist_lengths))
+}
+
+fn read_dynamic_trees(reader: &mut BitReader) -> Result<(HuffmanTree, HuffmanTree), String> {
+    let hlit = try!(reader.read_bits(5)) as usize + 257;
+    let hdist = try!(reader.read_bits(5)) as usize + 1;
+    let hclen = try!(reader.read_bits(4)) as usize + 4;
+
+    let mut code_length_lengths = [0u8; 19];
+    for i in 0..hclen {
+        code_length_lengths[CODE_LENGTH_ORDER[i]] = try!(reader.read_bits(3)) as u8;
+    }
+    let code_length_tree = HuffmanTree::from_lengths(&code_length_lengths);
+
+    let mut lengths = Vec::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let symbol = try!(code_length_tree.decode(reader));
+        match symbol {
+            0..=15 => lengths.push(symbol as u8),
+            16 => {
+                let repeat = try!(reader.read_bits(2)) + 3;
+                let prev = *try!(lengths.last().ok_or_else(|| "png: invalid repeat code".to_string()));
+                for _ in 0..repeat {
+                    lengths.push(prev);
+                }
+            }
+            17 => {
+                let repeat = try!(reader.read_bits(3)) + 3;
+                for _ in 0..repeat {
+                    lengths.push(0);
+                }
+            }
+            18 => {
+                let repeat = try!(reader.read_bits(7)) + 11;
+                for _ in 0..repeat {
+                    lengths.push(0);
+                }
+            }
+            other => return Err(format!("png: invalid code length symbol {}", other)),
+        }
+    }
+
+    let lit_tree = HuffmanTree::from_lengths(&lengths[0..hlit]);
+    let dist_tree = HuffmanTree::from_lengths(&lengths[hlit..hlit + hdist]);
+    Ok((lit_tree, dist_tree))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    lit_tree: &HuffmanTree,
+    dist_tree: &HuffmanTree,
+    out: &mut Vec<u8>,
+) -> Result<(), String> {
+    loop {
+        let symbol = try!(lit_tree.decode(reader));
+        if symbol < 256 {
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let index = (symbol - 257) as usize;
+            if index >= LENGTH_BASE.len() {
+                return Err("png: invalid length symbol".to_string());
+            }
+            let length = LENGTH_BASE[index] as usize + try!(reader.read_bits(LENGTH_EXTRA[index] as u32)) as usize;
+
+            let dist_symbol = try!(dist_tree.decode(reader)) as usize;
+            if dist_symbol >= DIST_BASE.len() {
+                return Err("png: invalid distance symbol".to_string());
+            }
+            let distance = DIST_BASE[dist_symbol] as usize + try!(reader.read_bits(DIST_EXTRA[dist_symbol] as u32)) as usize;
+
+            if distance > out.len() {
+                return Err("png: back-reference distance out of range".to_string());
+            }
+            let start = out.len() - distance;
+            for i in 0..length {
+                let byte = out[start + i];
+                out.push(byte);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use orbclient::{Color, Renderer};
+
+    use Image;
+    use ImageError;
+
+    use super::{encode, parse};
+
+    #[test]
+    fn round_trip_encode_parse() {
+        let mut image = Image::new(4, 3);
+        for y in 0..3 {
+            for x in 0..4 {
+                let color = Color::rgba((x * 50) as u8, (y * 80) as u8, 200, 255);
+                image.data_mut()[y * 4 + x] = color;
+            }
+        }
+
+        let encoded = encode(&image).expect("encode should succeed");
+        let decoded = parse(&encoded).expect("parse should succeed");
+
+        assert_eq!(decoded.width(), image.width());
+        assert_eq!(decoded.height(), image.height());
+        assert_eq!(decoded.data(), image.data());
+    }
+
+    #[test]
+    fn missing_idat_returns_error_instead_of_panicking() {
+        let image = Image::new(1, 1);
+        let encoded = encode(&image).expect("encode should succeed");
+
+        // Drop the IDAT chunk entirely: signature (8) + IHDR chunk (8 + 13 + 4)
+        // leaves the IEND chunk, which is exactly what a truncated/crafted
+        // file missing its image data would look like.
+        let ihdr_chunk_len = 8 + 13 + 4;
+        let mut truncated = Vec::new();
+        truncated.extend_from_slice(&encoded[0..8 + ihdr_chunk_len]);
+        truncated.extend_from_slice(&encoded[encoded.len() - 12..]); // IEND chunk
+
+        match parse(&truncated) {
+            Err(ImageError::NotEnoughData) => {}
+            other => panic!("expected ImageError::NotEnoughData, got {:?}", other),
+        }
+    }
+}